@@ -2,7 +2,6 @@ use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock, Weak};
 
-use itertools::Itertools;
 use metrics::{counter, gauge, histogram, increment_counter};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
@@ -21,15 +20,20 @@ pub struct TopQuery {
     pub rows_written: u64,
     pub rows_read: u64,
     pub query: String,
+    /// Fingerprint of `query`'s normalized template, used to dedup raw-text variants of the same
+    /// parameterized statement. See `fingerprint_query`.
+    #[serde(skip)]
+    pub fingerprint: u64,
 }
 
 impl TopQuery {
-    pub fn new(query: String, rows_read: u64, rows_written: u64) -> Self {
+    pub fn new(fingerprint: u64, query: String, rows_read: u64, rows_written: u64) -> Self {
         Self {
             weight: rows_read + rows_written,
             rows_read,
             rows_written,
             query,
+            fingerprint,
         }
     }
 }
@@ -40,54 +44,193 @@ pub struct SlowestQuery {
     pub query: String,
     pub rows_written: u64,
     pub rows_read: u64,
+    /// Fingerprint of `query`'s normalized template, used to dedup raw-text variants of the same
+    /// parameterized statement. See `fingerprint_query`.
+    #[serde(skip)]
+    pub fingerprint: u64,
 }
 
 impl SlowestQuery {
-    pub fn new(query: String, elapsed_ms: u64, rows_read: u64, rows_written: u64) -> Self {
+    pub fn new(
+        fingerprint: u64,
+        query: String,
+        elapsed_ms: u64,
+        rows_read: u64,
+        rows_written: u64,
+    ) -> Self {
         Self {
             elapsed_ms,
             query,
             rows_read,
             rows_written,
+            fingerprint,
         }
     }
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+/// Relative accuracy a `LatencySketch` bucket guarantees: any value falling in bucket `i` is
+/// estimated as `2 * GAMMA.powi(i) / (GAMMA + 1)`, within this fraction of its true value.
+const DDSKETCH_ALPHA: f64 = 0.01;
+
+/// Maximum number of distinct buckets a single sketch may hold, so a pathological spread of
+/// latencies can't grow a sketch's serialized footprint without bound. Observations that would
+/// open a new bucket past this cap fold into the closest bucket already tracked instead.
+const DDSKETCH_MAX_BUCKETS: usize = 2048;
+
+/// A mergeable relative-error quantile sketch (DDSketch) over per-query latencies, kept alongside
+/// the running sum/count in `QueryStats` so tail latencies (p90, p99) survive the periodic
+/// `try_persist_stats` round-trip and cross-process aggregation, which a running mean can't give
+/// you. Bucket `i` holds the count of observations whose value fell in
+/// `((GAMMA^(i-1), GAMMA^i]`; merging two sketches is just summing bucket counts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LatencySketch {
+    buckets: HashMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+}
+
+impl LatencySketch {
+    fn gamma() -> f64 {
+        (1.0 + DDSKETCH_ALPHA) / (1.0 - DDSKETCH_ALPHA)
+    }
+
+    fn bucket_index(v: f64) -> i32 {
+        (v.ln() / Self::gamma().ln()).ceil() as i32
+    }
+
+    /// Record one latency observation, in milliseconds.
+    pub fn record(&mut self, elapsed_ms: u64) {
+        self.count += 1;
+
+        if elapsed_ms == 0 {
+            self.zero_count += 1;
+            return;
+        }
+
+        let mut index = Self::bucket_index(elapsed_ms as f64);
+        if !self.buckets.contains_key(&index) && self.buckets.len() >= DDSKETCH_MAX_BUCKETS {
+            index = *self
+                .buckets
+                .keys()
+                .min_by_key(|&&existing| (existing - index).abs())
+                .expect("cap is non-zero");
+        }
+
+        *self.buckets.entry(index).or_insert(0) += 1;
+    }
+
+    /// Merge `other`'s bucket counts into `self`. This is all DDSketch merging requires, which is
+    /// why the sketch survives being persisted and reloaded, or aggregated across processes.
+    pub fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.zero_count += other.zero_count;
+        for (&index, &count) in &other.buckets {
+            *self.buckets.entry(index).or_insert(0) += count;
+        }
+    }
+
+    /// Estimate the `q`-quantile (`0.0..=1.0`) of recorded latencies, in milliseconds.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let target = (q * self.count as f64).ceil() as u64;
+        let mut seen = self.zero_count;
+        if seen >= target {
+            return 0;
+        }
+
+        let mut indices = self.buckets.keys().copied().collect::<Vec<_>>();
+        indices.sort_unstable();
+        let gamma = Self::gamma();
+        for index in indices {
+            seen += self.buckets[&index];
+            if seen >= target {
+                return (2.0 * gamma.powi(index) / (gamma + 1.0)).round() as u64;
+            }
+        }
+
+        0
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct QueryStats {
     pub elapsed_ms: u64,
     pub count: u64,
     pub rows_written: u64,
     pub rows_read: u64,
+    /// Distribution of this query's latencies, for `p50_ms`/`p90_ms`/`p99_ms`.
+    pub latency: LatencySketch,
 }
 
 impl QueryStats {
     pub fn new(elapsed_ms: u64, rows_read: u64, rows_written: u64) -> Self {
+        let mut latency = LatencySketch::default();
+        latency.record(elapsed_ms);
         Self {
             elapsed_ms,
             count: 1,
             rows_read,
             rows_written,
+            latency,
         }
     }
+
     pub fn merge(&self, another: &QueryStats) -> Self {
+        let mut latency = self.latency.clone();
+        latency.merge(&another.latency);
         Self {
             elapsed_ms: self.elapsed_ms + another.elapsed_ms,
             count: self.count + another.count,
             rows_read: self.rows_read + another.rows_read,
             rows_written: self.rows_written + another.rows_written,
+            latency,
         }
     }
+
+    pub fn p50_ms(&self) -> u64 {
+        self.latency.quantile(0.5)
+    }
+
+    pub fn p90_ms(&self) -> u64 {
+        self.latency.quantile(0.9)
+    }
+
+    pub fn p99_ms(&self) -> u64 {
+        self.latency.quantile(0.99)
+    }
+}
+
+/// Number of query templates the Space-Saving summary tracks at once.
+const QUERIES_STATS_CAPACITY: usize = 30;
+
+/// A query template tracked by the Space-Saving summary: a representative raw SQL example, its
+/// stats (with `elapsed_ms` doubling as the Space-Saving weight), and `epsilon`, the maximum
+/// amount `elapsed_ms` could be overestimated by. `epsilon` is non-zero only for a fingerprint
+/// that was inserted by evicting another entry, in which case it inherited that entry's weight as
+/// a floor; it's the confidence bound Space-Saving guarantees alongside the estimate.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeavyHitter {
+    pub example: String,
+    pub stats: QueryStats,
+    pub epsilon: u64,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct QueriesStats {
     #[serde(default)]
     id: Option<Uuid>,
+    /// Keyed by `fingerprint_query(sql)` rather than the raw SQL text, so bound-literal variants
+    /// of the same statement aggregate into a single entry instead of bloating the summary with
+    /// near-duplicates.
     #[serde(default)]
-    stats_threshold: AtomicU64,
-    #[serde(default)]
-    stats: HashMap<String, QueryStats>,
+    stats: HashMap<u64, HeavyHitter>,
+    /// `stats` indexed by (elapsed_ms, fingerprint), so the current minimum-weight entry can be
+    /// found in O(log CAPACITY) instead of sorting the whole summary on every eviction.
+    #[serde(skip)]
+    by_weight: BTreeSet<(u64, u64)>,
 }
 
 impl QueriesStats {
@@ -97,46 +240,209 @@ impl QueriesStats {
         Arc::new(RwLock::new(this))
     }
 
-    pub fn register_query(&mut self, sql: &String, stat: QueryStats) {
-        let (aggregated, new) = match self.stats.get(sql) {
-            Some(aggregated) => (aggregated.merge(&stat), false),
-            None => (stat, true),
-        };
-
-        if aggregated.elapsed_ms < self.stats_threshold.load(Ordering::Relaxed) {
-            return;
-        }
-
-        self.stats.insert(sql.clone(), aggregated);
-
-        if !new || self.stats.len() <= 30 {
+    /// Space-Saving (Misra-Gries variant) top-K tracking, keyed on `fingerprint_query(sql)`: merge
+    /// into an already-tracked template, insert a new one directly while there's room, or
+    /// otherwise evict the current minimum-weight template and have the new one inherit its
+    /// weight as a floor. This guarantees any template whose true cumulative `elapsed_ms` exceeds
+    /// total/CAPACITY is retained, at O(log CAPACITY) per update with no full-summary clone.
+    pub fn register_query(&mut self, sql: &str, stat: QueryStats) {
+        let fingerprint = fingerprint_query(sql);
+
+        if let Some(hitter) = self.stats.get_mut(&fingerprint) {
+            self.by_weight
+                .remove(&(hitter.stats.elapsed_ms, fingerprint));
+            hitter.stats = hitter.stats.merge(&stat);
+            self.by_weight
+                .insert((hitter.stats.elapsed_ms, fingerprint));
             return;
         }
 
-        let mut vec = self.stats.clone().into_iter().collect_vec();
-        vec.sort_by(|a, b| a.1.cmp(&b.1));
-        let len = vec.len();
-        if len <= 30 {
+        if self.stats.len() < QUERIES_STATS_CAPACITY {
+            self.by_weight.insert((stat.elapsed_ms, fingerprint));
+            self.stats.insert(
+                fingerprint,
+                HeavyHitter {
+                    example: sql.to_string(),
+                    stats: stat,
+                    epsilon: 0,
+                },
+            );
             return;
         }
 
-        for i in 0..len - 30 {
-            self.stats.remove(&vec[i].0);
-        }
-
-        self.stats_threshold
-            .store(vec[len - 30].1.elapsed_ms, Ordering::Relaxed);
+        let &(evicted_weight, evicted_fingerprint) =
+            self.by_weight.iter().next().expect("capacity is non-zero");
+        self.by_weight.remove(&(evicted_weight, evicted_fingerprint));
+        self.stats.remove(&evicted_fingerprint);
+
+        let mut stat = stat;
+        stat.elapsed_ms += evicted_weight;
+        self.by_weight.insert((stat.elapsed_ms, fingerprint));
+        self.stats.insert(
+            fingerprint,
+            HeavyHitter {
+                example: sql.to_string(),
+                stats: stat,
+                epsilon: evicted_weight,
+            },
+        );
     }
 
     pub fn id(&self) -> Option<Uuid> {
         self.id
     }
 
-    pub fn stats(&self) -> &HashMap<String, QueryStats> {
+    pub fn stats(&self) -> &HashMap<u64, HeavyHitter> {
         &self.stats
     }
 }
 
+/// Canonicalize `sql` into a reusable template: string and numeric literals collapse to a single
+/// `?` placeholder, a parenthesized list of placeholders (e.g. an `IN (?, ?, ?)` list) collapses
+/// to one `(?)`, and runs of whitespace fold to a single space. Two executions of the same
+/// statement bound to different literals always normalize to the same template.
+fn normalize_query(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut last_was_space = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            // string literal: consume up to its closing quote, treating '' as an escaped quote
+            // rather than the end of the literal.
+            '\'' => {
+                while let Some(next) = chars.next() {
+                    if next == '\'' {
+                        if chars.peek() == Some(&'\'') {
+                            chars.next();
+                            continue;
+                        }
+                        break;
+                    }
+                }
+                out.push('?');
+                last_was_space = false;
+            }
+            // numeric literal: only a digit run that starts a literal (preceded by whitespace, an
+            // operator/punctuation, `(`, `,`, or nothing) collapses; consume the whole run (and
+            // any embedded decimal point), so `3.14` collapses to a single placeholder rather than
+            // two.
+            c if c.is_ascii_digit()
+                && !matches!(out.chars().next_back(), Some(p) if p.is_ascii_alphanumeric() || p == '_') =>
+            {
+                while matches!(chars.peek(), Some(c) if c.is_ascii_digit() || *c == '.') {
+                    chars.next();
+                }
+                out.push('?');
+                last_was_space = false;
+            }
+            // a digit immediately following an identifier character is part of that identifier
+            // (e.g. the `1`/`2` in `t1`/`t2`), not a literal: queries against different tables
+            // must not fingerprint identically.
+            c if c.is_ascii_digit() => {
+                out.push(c);
+                last_was_space = false;
+            }
+            c if c.is_whitespace() => {
+                if !last_was_space {
+                    out.push(' ');
+                    last_was_space = true;
+                }
+            }
+            c => {
+                out.push(c);
+                last_was_space = false;
+            }
+        }
+    }
+
+    collapse_placeholder_lists(out.trim())
+}
+
+/// Collapse a parenthesized run of placeholders and separators (e.g. `(?, ?, ?)`, as produced by
+/// an `IN (...)` list) down to a single `(?)`, so lists differing only in length still fold into
+/// the same template.
+fn collapse_placeholder_lists(template: &str) -> String {
+    let chars = template.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(template.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] == '(' {
+            let mut j = i + 1;
+            let mut is_placeholder_list = j < chars.len();
+            while j < chars.len() && chars[j] != ')' {
+                if !matches!(chars[j], '?' | ',' | ' ') {
+                    is_placeholder_list = false;
+                    break;
+                }
+                j += 1;
+            }
+
+            if is_placeholder_list && j < chars.len() && chars[i + 1..j].contains(&'?') {
+                out.push_str("(?)");
+                i = j + 1;
+                continue;
+            }
+        }
+
+        out.push(chars[i]);
+        i += 1;
+    }
+
+    out
+}
+
+/// Hash of `sql`'s normalized template (see `normalize_query`), used to key query aggregation so
+/// that parameterized variants of the same statement are counted together.
+fn fingerprint_query(sql: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    normalize_query(sql).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A point-in-time, plain-value snapshot of everything `Stats` tracks for a namespace, for the
+/// admin `/stats/<namespace>` endpoint. Unlike `Stats` itself this holds no atomics or locks, so
+/// it can be serialized and handed to a client without any of them observing further updates.
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub namespace: String,
+    pub rows_read: u64,
+    pub rows_written: u64,
+    pub write_requests_delegated: u64,
+    pub embedded_replica_frames_replicated: u64,
+    pub current_frame_no: FrameNo,
+    /// Rows-read-plus-written weight a query must clear to displace the current top-10; 0 until
+    /// an 11th distinct template first pushes the table past 10 entries.
+    pub top_query_threshold: u64,
+    pub top_queries: Vec<TopQuery>,
+    /// Elapsed-ms a query must clear to displace the current slowest-10; 0 until an 11th
+    /// distinct template first pushes the table past 10 entries.
+    pub slowest_query_threshold: u64,
+    pub slowest_queries: Vec<SlowestQuery>,
+    /// Every template currently tracked by the Space-Saving summary, quantiles precomputed.
+    pub queries: Vec<HeavyHitterSnapshot>,
+}
+
+/// One `QueriesStats` entry, flattened for serialization with its latency quantiles computed up
+/// front so a client doesn't need to reimplement `LatencySketch::quantile`.
+#[derive(Debug, Serialize)]
+pub struct HeavyHitterSnapshot {
+    pub fingerprint: u64,
+    pub example: String,
+    pub count: u64,
+    pub rows_read: u64,
+    pub rows_written: u64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    /// Space-Saving's overestimate bound: the cumulative `elapsed_ms` the summary tracks for this
+    /// template (not itself part of this snapshot) exceeds the true value by at most `epsilon`.
+    pub epsilon: u64,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct StatsUpdateMessage {
     pub sql: String,
@@ -247,6 +553,8 @@ impl Stats {
             );
         }
 
+        let fingerprint = fingerprint_query(&sql);
+
         self.inc_rows_read(rows_read);
         self.inc_rows_written(rows_written);
         self.inc_query(elapsed_ms);
@@ -256,6 +564,7 @@ impl Stats {
         );
         if self.qualifies_as_top_query(weight) {
             self.add_top_query(crate::stats::TopQuery::new(
+                fingerprint,
                 sql.clone(),
                 rows_read,
                 rows_written,
@@ -263,6 +572,7 @@ impl Stats {
         }
         if self.qualifies_as_slowest_query(elapsed_ms) {
             self.add_slowest_query(crate::stats::SlowestQuery::new(
+                fingerprint,
                 sql.clone(),
                 elapsed_ms,
                 rows_read,
@@ -356,7 +666,7 @@ impl Stats {
         &self.queries
     }
 
-    pub(crate) fn register_query(&self, sql: &String, stat: QueryStats) {
+    pub(crate) fn register_query(&self, sql: &str, stat: QueryStats) {
         self.queries.write().unwrap().register_query(sql, stat)
     }
 
@@ -368,6 +678,18 @@ impl Stats {
             query.rows_written,
             query.query
         );
+        // keep only the highest-weight occurrence of each template, so raw-text variants of the
+        // same parameterized statement don't crowd out other templates in the top-N.
+        if let Some(existing) = top_queries
+            .iter()
+            .find(|q| q.fingerprint == query.fingerprint)
+            .cloned()
+        {
+            if existing.weight >= query.weight {
+                return;
+            }
+            top_queries.remove(&existing);
+        }
         top_queries.insert(query);
         if top_queries.len() > 10 {
             top_queries.pop_first();
@@ -392,6 +714,18 @@ impl Stats {
     pub(crate) fn add_slowest_query(&self, query: SlowestQuery) {
         let mut slowest_queries = self.slowest_queries.write().unwrap();
         tracing::debug!("slowest query: {}: {}", query.elapsed_ms, query.query);
+        // keep only the slowest occurrence of each template, so raw-text variants of the same
+        // parameterized statement don't crowd out other templates in the top-N.
+        if let Some(existing) = slowest_queries
+            .iter()
+            .find(|q| q.fingerprint == query.fingerprint)
+            .cloned()
+        {
+            if existing.elapsed_ms >= query.elapsed_ms {
+                return;
+            }
+            slowest_queries.remove(&existing);
+        }
         slowest_queries.insert(query);
         if slowest_queries.len() > 10 {
             slowest_queries.pop_first();
@@ -434,6 +768,52 @@ impl Stats {
     pub fn id(&self) -> Option<Uuid> {
         self.id
     }
+
+    /// Snapshot everything the admin `/stats/<namespace>` endpoint reports: the top and slowest
+    /// query tables, their thresholds, and the quantile-annotated Space-Saving summary.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let queries = self
+            .queries
+            .read()
+            .unwrap()
+            .stats()
+            .iter()
+            .map(|(&fingerprint, hitter)| HeavyHitterSnapshot {
+                fingerprint,
+                example: hitter.example.clone(),
+                count: hitter.stats.count,
+                rows_read: hitter.stats.rows_read,
+                rows_written: hitter.stats.rows_written,
+                p50_ms: hitter.stats.p50_ms(),
+                p90_ms: hitter.stats.p90_ms(),
+                p99_ms: hitter.stats.p99_ms(),
+                epsilon: hitter.epsilon,
+            })
+            .collect();
+
+        StatsSnapshot {
+            namespace: self.namespace.to_string(),
+            rows_read: self.rows_read(),
+            rows_written: self.rows_written(),
+            write_requests_delegated: self.write_requests_delegated(),
+            embedded_replica_frames_replicated: self.get_embedded_replica_frames_replicated(),
+            current_frame_no: self.get_current_frame_no(),
+            top_query_threshold: self.top_query_threshold.load(Ordering::Relaxed),
+            top_queries: self.top_queries.read().unwrap().iter().cloned().collect(),
+            slowest_query_threshold: self.slowest_query_threshold.load(Ordering::Relaxed),
+            slowest_queries: self.slowest_queries.read().unwrap().iter().cloned().collect(),
+            queries,
+        }
+    }
+
+    /// Reset the top-queries and slowest-queries tables, for the admin reset endpoint: a
+    /// dashboard that windows these tables (e.g. "slowest queries in the last hour") calls this
+    /// after each scrape instead of waiting on `add_top_query`/`add_slowest_query`'s own
+    /// threshold-driven eviction to churn the tables on its own.
+    pub fn reset_query_tables(&self) {
+        self.reset_top_queries();
+        self.reset_slowest_queries();
+    }
 }
 
 async fn spawn_stats_persist_thread(stats: Weak<Stats>, path: PathBuf) -> anyhow::Result<()> {
@@ -0,0 +1,125 @@
+//! Admin HTTP surface for the `Stats` subsystem, mirroring the dedicated admin metrics router
+//! rather than sharing a port with client-facing traffic: a Prometheus-format `/metrics` scrape
+//! target across every open namespace, and a per-namespace `/stats/<namespace>` JSON view with
+//! the top/slowest query tables and the quantile-annotated Space-Saving summary. A POST resets
+//! the top/slowest query tables (not the Space-Saving summary) so a dashboard can window them.
+
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+
+use crate::stats::{Stats, StatsSnapshot};
+
+/// Looks up a namespace's `Stats` by name and enumerates every namespace currently open, so this
+/// router doesn't need to depend on however the rest of the server tracks open namespaces.
+pub trait StatsSource: Send + Sync + 'static {
+    fn get(&self, namespace: &str) -> Option<Arc<Stats>>;
+    fn all(&self) -> Vec<Arc<Stats>>;
+}
+
+pub fn router<S: StatsSource>(source: Arc<S>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics::<S>))
+        .route(
+            "/stats/:namespace",
+            get(stats_for_namespace::<S>).post(reset_namespace_stats::<S>),
+        )
+        .with_state(source)
+}
+
+async fn metrics<S: StatsSource>(State(source): State<Arc<S>>) -> impl IntoResponse {
+    let mut out = String::new();
+    write_help(&mut out, "libsql_server_rows_read", "Rows read since the namespace was created.", "counter");
+    write_help(&mut out, "libsql_server_rows_written", "Rows written since the namespace was created.", "counter");
+    write_help(&mut out, "libsql_server_write_requests_delegated", "Write requests delegated from a replica to its primary.", "counter");
+    write_help(&mut out, "libsql_server_embedded_replica_frames_replicated", "Frames replicated to an embedded replica.", "counter");
+    write_help(&mut out, "libsql_server_current_frame_no", "Most recent frame_no applied to this namespace.", "gauge");
+    write_help(&mut out, "libsql_server_top_query_threshold", "Rows read+written a query must clear to enter the top-10 table.", "gauge");
+    write_help(&mut out, "libsql_server_slowest_query_threshold", "Elapsed ms a query must clear to enter the slowest-10 table.", "gauge");
+    write_help(&mut out, "libsql_server_query_stats_count", "Executions of a query template tracked by the Space-Saving summary.", "counter");
+    write_help(&mut out, "libsql_server_query_stats_p50_ms", "Estimated p50 latency, in milliseconds, of a tracked query template.", "gauge");
+    write_help(&mut out, "libsql_server_query_stats_p90_ms", "Estimated p90 latency, in milliseconds, of a tracked query template.", "gauge");
+    write_help(&mut out, "libsql_server_query_stats_p99_ms", "Estimated p99 latency, in milliseconds, of a tracked query template.", "gauge");
+    write_help(&mut out, "libsql_server_query_stats_epsilon", "Space-Saving overestimate bound on a tracked query template's cumulative elapsed_ms.", "gauge");
+
+    for stats in source.all() {
+        write_namespace_metrics(&stats.snapshot(), &mut out);
+    }
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        out,
+    )
+}
+
+fn write_help(out: &mut String, name: &str, help: &str, ty: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {ty}");
+}
+
+fn write_namespace_metrics(snapshot: &StatsSnapshot, out: &mut String) {
+    let ns = &snapshot.namespace;
+    let _ = writeln!(out, "libsql_server_rows_read{{namespace=\"{ns}\"}} {}", snapshot.rows_read);
+    let _ = writeln!(out, "libsql_server_rows_written{{namespace=\"{ns}\"}} {}", snapshot.rows_written);
+    let _ = writeln!(
+        out,
+        "libsql_server_write_requests_delegated{{namespace=\"{ns}\"}} {}",
+        snapshot.write_requests_delegated
+    );
+    let _ = writeln!(
+        out,
+        "libsql_server_embedded_replica_frames_replicated{{namespace=\"{ns}\"}} {}",
+        snapshot.embedded_replica_frames_replicated
+    );
+    let _ = writeln!(out, "libsql_server_current_frame_no{{namespace=\"{ns}\"}} {}", snapshot.current_frame_no);
+    let _ = writeln!(
+        out,
+        "libsql_server_top_query_threshold{{namespace=\"{ns}\"}} {}",
+        snapshot.top_query_threshold
+    );
+    let _ = writeln!(
+        out,
+        "libsql_server_slowest_query_threshold{{namespace=\"{ns}\"}} {}",
+        snapshot.slowest_query_threshold
+    );
+
+    for query in &snapshot.queries {
+        let fp = query.fingerprint;
+        let _ = writeln!(out, "libsql_server_query_stats_count{{namespace=\"{ns}\",fingerprint=\"{fp}\"}} {}", query.count);
+        let _ = writeln!(out, "libsql_server_query_stats_p50_ms{{namespace=\"{ns}\",fingerprint=\"{fp}\"}} {}", query.p50_ms);
+        let _ = writeln!(out, "libsql_server_query_stats_p90_ms{{namespace=\"{ns}\",fingerprint=\"{fp}\"}} {}", query.p90_ms);
+        let _ = writeln!(out, "libsql_server_query_stats_p99_ms{{namespace=\"{ns}\",fingerprint=\"{fp}\"}} {}", query.p99_ms);
+        let _ = writeln!(out, "libsql_server_query_stats_epsilon{{namespace=\"{ns}\",fingerprint=\"{fp}\"}} {}", query.epsilon);
+    }
+}
+
+async fn stats_for_namespace<S: StatsSource>(
+    State(source): State<Arc<S>>,
+    Path(namespace): Path<String>,
+) -> impl IntoResponse {
+    match source.get(&namespace) {
+        Some(stats) => Json(stats.snapshot()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// `POST /stats/<namespace>`: clear the top-queries and slowest-queries tables for `namespace`,
+/// for a dashboard that windows them rather than watching them grow for the lifetime of the
+/// process.
+async fn reset_namespace_stats<S: StatsSource>(
+    State(source): State<Arc<S>>,
+    Path(namespace): Path<String>,
+) -> impl IntoResponse {
+    match source.get(&namespace) {
+        Some(stats) => {
+            stats.reset_query_tables();
+            StatusCode::NO_CONTENT
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
@@ -0,0 +1,99 @@
+//! A hybrid logical clock (HLC) for ordering transactions across a primary and its replicas.
+//! Wall-clock time alone isn't safe to compare across nodes (clocks skew), and a pure logical
+//! counter carries no relation to real time; an HLC combines both, so timestamps stamped by
+//! different nodes are still totally ordered and stay close to wall-clock time when clocks agree.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bits allocated to the logical counter, the low bits of a timestamp's physical/logical half.
+/// 16 bits is generous headroom for however many commits land within the same millisecond.
+const COUNTER_BITS: u32 = 16;
+const COUNTER_MASK: u64 = (1 << COUNTER_BITS) - 1;
+
+/// An HLC timestamp: physical time (milliseconds since the Unix epoch) in the high bits, a
+/// logical counter in the low bits, paired with the id of the node that stamped it. `node_id`
+/// only matters as a tie-breaker between two timestamps whose physical/logical halves are
+/// otherwise identical; ordering by `(physical_and_logical, node_id)`, which the derived `Ord`
+/// does field-by-field, gives a total order across the whole cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HlcTimestamp {
+    physical_and_logical: u64,
+    pub node_id: u64,
+}
+
+impl HlcTimestamp {
+    /// The physical-time component, in milliseconds since the Unix epoch.
+    pub fn physical_ms(&self) -> u64 {
+        self.physical_and_logical >> COUNTER_BITS
+    }
+
+    /// The logical counter, distinguishing timestamps stamped within the same millisecond.
+    pub fn logical(&self) -> u64 {
+        self.physical_and_logical & COUNTER_MASK
+    }
+}
+
+/// A node-local hybrid logical clock, bumped on every observation so it stays monotonic even if
+/// the wall clock doesn't move forward (or moves backward).
+pub struct HybridLogicalClock {
+    node_id: u64,
+    last: AtomicU64,
+}
+
+impl HybridLogicalClock {
+    pub fn new(node_id: u64) -> Self {
+        Self {
+            node_id,
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Stamp a new timestamp, bumping the clock forward from both its own last value and
+    /// `observed` (an HLC timestamp received from elsewhere, e.g. a replicated commit), so the
+    /// clock never goes backward relative to anything it has seen: if wall-clock time has moved
+    /// past the highest physical time seen so far, the new timestamp starts a fresh logical
+    /// counter at that physical time; otherwise it's the highest timestamp seen so far plus one,
+    /// carrying the logical counter forward instead.
+    pub fn observe(&self, observed: Option<HlcTimestamp>) -> HlcTimestamp {
+        let physical_now = now_millis();
+        let observed_packed = observed.map_or(0, |ts| ts.physical_and_logical);
+
+        let mut prev = self.last.load(Ordering::SeqCst);
+        loop {
+            let baseline = prev.max(observed_packed);
+            let baseline_physical = baseline >> COUNTER_BITS;
+
+            let next = if physical_now > baseline_physical {
+                physical_now << COUNTER_BITS
+            } else {
+                baseline + 1
+            };
+
+            match self
+                .last
+                .compare_exchange_weak(prev, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    return HlcTimestamp {
+                        physical_and_logical: next,
+                        node_id: self.node_id,
+                    };
+                }
+                Err(actual) => prev = actual,
+            }
+        }
+    }
+
+    /// Stamp a new timestamp without merging in anything observed externally.
+    pub fn now(&self) -> HlcTimestamp {
+        self.observe(None)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -1,12 +1,12 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 
 use arc_swap::ArcSwap;
-use crossbeam::deque::Injector;
-use crossbeam::sync::Unparker;
+use crossbeam::sync::{Parker, Unparker};
 use fst::Streamer;
 use fst::map::OpBuilder;
 use libsql_sys::ffi::Sqlite3DbHeader;
@@ -14,25 +14,135 @@ use libsql_sys::wal::PageHeaders;
 use parking_lot::{Mutex, RwLock};
 use zerocopy::FromBytes;
 
+use crate::clock::{HlcTimestamp, HybridLogicalClock};
+use crate::crypto::PageCipher;
 use crate::error::Error;
 use crate::file::FileExt;
-use crate::log::{Log, index_entry_split};
+use crate::log::Compactor;
+use crate::log::Log;
 use crate::log::SealedLog;
 use crate::name::NamespaceName;
 use crate::registry::WalRegistry;
 use crate::transaction::Transaction;
 use crate::transaction::{ReadTransaction, Savepoint, WriteTransaction};
 
+/// A strict FIFO ticket queue for writers waiting on `WalLock`. Unlike a work-stealing
+/// `Injector`, tickets are served in the exact order they were issued: the releasing writer
+/// always wakes whichever waiter holds the next ticket, so no writer can be passed over no
+/// matter how long it's been waiting.
+#[derive(Default)]
+struct WaitQueue {
+    next_ticket: AtomicU64,
+    /// the ticket currently allowed to take the lock
+    front_ticket: AtomicU64,
+    parked: Mutex<BTreeMap<u64, Unparker>>,
+}
+
+impl WaitQueue {
+    /// Take the next ticket and block until it reaches the front of the queue.
+    fn wait_for_turn(&self) -> u64 {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        loop {
+            if self.front_ticket.load(Ordering::Acquire) == ticket {
+                return ticket;
+            }
+
+            let parker = Parker::new();
+            {
+                let mut parked = self.parked.lock();
+                // re-check under the lock: the ticket may have become current between our load
+                // above and taking this lock, in which case we must not park forever.
+                if self.front_ticket.load(Ordering::Acquire) == ticket {
+                    return ticket;
+                }
+                parked.insert(ticket, parker.unparker().clone());
+            }
+            parker.park();
+        }
+    }
+
+    /// Release `ticket` and wake whoever now holds the next one, if anyone is waiting.
+    fn advance_past(&self, ticket: u64) {
+        let next = ticket + 1;
+        self.front_ticket.store(next, Ordering::Release);
+        if let Some(unparker) = self.parked.lock().remove(&next) {
+            unparker.unpark();
+        }
+    }
+
+    /// Whether a ticket has been handed out for a writer still queued behind the one currently
+    /// at the front, i.e. whether releasing the lock right now would hand it to someone.
+    fn has_waiters(&self) -> bool {
+        self.next_ticket.load(Ordering::Acquire) > self.front_ticket.load(Ordering::Acquire) + 1
+    }
+}
+
+/// The write lock for a `SharedWal`: at most one connection may hold `tx_id` at a time, and
+/// `waiters` hands it out in strict arrival order.
+#[derive(Default)]
+pub struct WalLock {
+    /// id of the connection currently holding the write lock, if any
+    pub tx_id: Mutex<Option<u64>>,
+    next_tx_id: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl WalLock {
+    /// Take a FIFO ticket, wait for it to come up, and grab the write lock. Returns the new
+    /// transaction id and the ticket that must later be passed to `release`.
+    fn acquire(&self) -> (u64, u64) {
+        let ticket = self.waiters.wait_for_turn();
+        let id = self.next_tx_id.fetch_add(1, Ordering::Relaxed);
+        *self.tx_id.lock() = Some(id);
+        (id, ticket)
+    }
+
+    /// Release the write lock and let the next queued writer, if any, take its turn.
+    pub(crate) fn release(&self, ticket: u64) {
+        self.tx_id.lock().take();
+        self.waiters.advance_past(ticket);
+    }
+
+    /// Whether a writer is currently queued waiting for this lock.
+    pub(crate) fn has_waiters(&self) -> bool {
+        self.waiters.has_waiters()
+    }
+}
+
 pub struct SharedWal {
     pub current: ArcSwap<Log>,
     pub segments: RwLock<VecDeque<SealedLog>>,
-    /// Current transaction id
-    pub tx_id: Arc<Mutex<Option<u64>>>,
-    pub next_tx_id: AtomicU64,
+    pub wal_lock: Arc<WalLock>,
     pub db_file: File,
-    pub waiters: Arc<Injector<Unparker>>,
+    /// Path `db_file` was opened from, kept around so sidecar files that live alongside it (like
+    /// `db_page_tags`'s persisted form) can be named relative to it without plumbing the path
+    /// through every call site that needs one.
+    pub db_file_path: PathBuf,
     pub namespace: NamespaceName,
     pub registry: Arc<WalRegistry>,
+    /// Namespace-wide page cipher, shared with every `Log`/`SealedLog` this wal owns. `None` if
+    /// the namespace was opened without an encryption key; `db_file` is then kept in plaintext,
+    /// same as before encryption support existed.
+    pub cipher: Option<Arc<PageCipher>>,
+    /// Authentication tags for pages checkpointed into `db_file`, keyed by page number.
+    /// `db_file`'s page slots keep their existing fixed size, so the tag lives out-of-band here
+    /// rather than stealing bytes from the page; unused when `cipher` is `None`. Durably mirrored
+    /// to `page_tags_path` by `persist_page_tags` at the end of every `checkpoint` that produces
+    /// new tags; whatever constructs a `SharedWal` is expected to seed this field from
+    /// `load_page_tags` before serving reads against an existing `db_file`.
+    db_page_tags: RwLock<BTreeMap<u32, [u8; 16]>>,
+    /// Log-rotation and auto-checkpoint thresholds for this namespace. Set by `WalRegistry` at
+    /// open time from its own `CheckpointConfig`.
+    pub checkpoint_config: CheckpointConfig,
+    /// Stamps every committed write with a hybrid logical clock timestamp, so a replica (or a
+    /// client reading from one) can tell how caught-up its snapshot is relative to the primary
+    /// in terms that are comparable across nodes, unlike a bare `frame_no`.
+    pub clock: HybridLogicalClock,
+    /// Maps each commit's HLC timestamp to the frame_no it committed at, so a caller that only
+    /// knows "as of timestamp T" can resolve it to a concrete snapshot via `frame_no_at`.
+    // TODO: persist this sidecar alongside db_file so a restart doesn't need to replay the WAL
+    // to recover it.
+    commit_hlc_index: RwLock<BTreeMap<HlcTimestamp, u64>>,
 }
 
 impl SharedWal {
@@ -57,75 +167,112 @@ impl SharedWal {
                 max_frame_no,
                 log: current.clone(),
                 db_size,
-                created_at: Instant::now()
+                created_at: Instant::now(),
+                observed_hlc: self.clock.now(),
             };
         }
     }
 
+    /// Optimistically upgrade a read transaction to a write transaction. If another writer has
+    /// committed since this transaction's snapshot was taken, the upgrade is refused with
+    /// `Error::BusySnapshot` and the caller must restart with a fresh read transaction; callers
+    /// that already know they're going to write should prefer `begin_write`, which never hits
+    /// this case because it takes the write lock before observing a snapshot at all.
     pub fn upgrade(&self, tx: &mut Transaction) -> Result<(), Error> {
         match tx {
             Transaction::Write(_) => todo!("already in a write transaction"),
             Transaction::Read(read_tx) => {
-                loop {
-                    let mut lock = self.tx_id.lock();
-                    match *lock {
-                        Some(id) => {
-                            // FIXME this is not ver fair, always enqueue to the queue before acquiring
-                            // lock
-                            tracing::trace!(
-                                "txn currently held by {id}, registering to wait queue"
-                            );
-                            let parker = crossbeam::sync::Parker::new();
-                            let unpaker = parker.unparker().clone();
-                            self.waiters.push(unpaker);
-                            drop(lock);
-                            parker.park();
-                        }
-                        None => {
-                            let id = self.next_tx_id.fetch_add(1, Ordering::Relaxed);
-                            // we read two fields in the header. There is no risk that a transaction commit in
-                            // between the two reads because this would require that:
-                            // 1) there would be a running txn
-                            // 2) that transaction held the lock to tx_id (be in a transaction critical section)
-                            let current = self.current.load();
-                            let last_commited = current.last_commited();
-                            if read_tx.max_frame_no != last_commited {
-                                return Err(Error::BusySnapshot);
-                            }
-                            let next_offset = current.frames_in_log() as u32;
-                            *lock = Some(id);
-                            *tx = Transaction::Write(WriteTransaction {
-                                id,
-                                lock: self.tx_id.clone(),
-                                savepoints: vec![Savepoint {
-                                    next_offset,
-                                    next_frame_no: last_commited + 1,
-                                    index: None,
-                                }],
-                                next_frame_no: last_commited + 1,
-                                next_offset,
-                                is_commited: false,
-                                read_tx: read_tx.clone(),
-                                waiters: self.waiters.clone(),
-                            });
-                            return Ok(());
-                        }
-                    }
+                let (id, ticket) = self.wal_lock.acquire();
+                // we read two fields in the header. There is no risk that a transaction commits in
+                // between the two reads because this would require that:
+                // 1) there would be a running txn
+                // 2) that transaction held the lock to tx_id (be in a transaction critical section)
+                let current = self.current.load();
+                let last_commited = current.last_commited();
+                if read_tx.max_frame_no != last_commited {
+                    self.wal_lock.release(ticket);
+                    return Err(Error::BusySnapshot);
                 }
+                let next_offset = current.frames_in_log() as u32;
+                *tx = Transaction::Write(WriteTransaction {
+                    id,
+                    wal_lock: self.wal_lock.clone(),
+                    ticket,
+                    savepoints: vec![Savepoint {
+                        name: None,
+                        next_offset,
+                        next_frame_no: last_commited + 1,
+                        index: Default::default(),
+                    }],
+                    next_frame_no: last_commited + 1,
+                    next_offset,
+                    is_commited: false,
+                    read_tx: read_tx.clone(),
+                    commit_hlc: None,
+                });
+                Ok(())
             }
         }
     }
 
-    pub fn read_frame(&self, tx: &Transaction, page_no: u32, buffer: &mut [u8]) {
+    /// Begin a transaction that is known up front to write, mirroring SQLite's
+    /// `BEGIN IMMEDIATE`: the write lock is acquired before any snapshot is taken, so unlike
+    /// `upgrade` this can never fail with `Error::BusySnapshot`.
+    #[tracing::instrument(skip_all)]
+    pub fn begin_write(&self) -> WriteTransaction {
+        let (id, ticket) = self.wal_lock.acquire();
+        let read_tx = self.begin_read();
+        let next_offset = read_tx.log.frames_in_log() as u32;
+        let next_frame_no = read_tx.max_frame_no + 1;
+        WriteTransaction {
+            id,
+            wal_lock: self.wal_lock.clone(),
+            ticket,
+            savepoints: vec![Savepoint {
+                name: None,
+                next_offset,
+                next_frame_no,
+                index: Default::default(),
+            }],
+            next_frame_no,
+            next_offset,
+            is_commited: false,
+            read_tx,
+            commit_hlc: None,
+        }
+    }
+
+    /// Begin a transaction that must run with no other writer *or* reader observing the
+    /// database concurrently, for operations like bootstrapping a namespace from a snapshot.
+    /// `SharedWal` only ever has one writer at a time already, so today this differs from
+    /// `begin_write` only in intent; it exists as its own entry point so it has somewhere to
+    /// grow actual reader-exclusion into later without changing call sites.
+    pub fn begin_exclusive(&self) -> WriteTransaction {
+        self.begin_write()
+    }
+
+    pub fn read_frame(&self, tx: &Transaction, page_no: u32, buffer: &mut [u8]) -> Result<(), Error> {
         match tx.log.find_frame(page_no, tx) {
-            Some((_, offset)) => tx.log.read_page_offset(offset, buffer),
+            Some(offset) => tx.log.read_page_offset(offset, buffer)?,
             None => {
                 // locate in segments
                 if !self.read_from_segments(page_no, tx.max_frame_no, buffer) {
-                    // read from db_file
+                    // read from db_file. A page past `db_size` as of this snapshot (e.g. a
+                    // backup taken against a `max_frame_no` that's since grown) surfaces here as
+                    // a plain I/O error instead of panicking the reading thread.
                     self.db_file
-                        .read_exact_at(buffer, (page_no as u64 - 1) * 4096)
-                        .unwrap();
+                        .read_exact_at(buffer, (page_no as u64 - 1) * 4096)?;
+                    if let Some(cipher) = &self.cipher {
+                        // a missing tag means the page was checkpointed in a process that
+                        // crashed before `persist_page_tags` made it durable; surface it the same
+                        // as a tag that doesn't authenticate, instead of panicking.
+                        let tag = *self
+                            .db_page_tags
+                            .read()
+                            .get(&page_no)
+                            .ok_or(Error::Decryption)?;
+                        cipher.open_checkpointed(page_no, &mut buffer[..4096 - 8], &tag)?;
+                    }
                 }
             }
         }
@@ -138,6 +285,73 @@ impl SharedWal {
         let frame_no = u64::from_be_bytes(buffer[4096 - 8..].try_into().unwrap());
         tracing::trace!(frame_no, tx = tx.max_frame_no, "read page");
         assert!(frame_no <= tx.max_frame_no);
+        Ok(())
+    }
+
+    /// Export a standalone, directly-openable SQLite database file reflecting this namespace's
+    /// current committed state, without blocking or even slowing down concurrent writers.
+    pub fn backup_latest(&self, dest: &Path) -> Result<(), Error> {
+        let tx = self.begin_read();
+        self.backup_to(dest, tx.max_frame_no)
+    }
+
+    /// Export a standalone, directly-openable SQLite database file reflecting this namespace's
+    /// state as of `max_frame_no`. This is the WAL-level analogue of SQLite's online backup API:
+    /// every page is resolved through the exact path an ordinary read transaction uses
+    /// (`find_frame` -> segments -> `db_file`), so the backup never has to pause an in-flight
+    /// writer. Every sealed segment we might touch is pinned via its `read_locks` count for the
+    /// duration, so `checkpoint` can't reclaim one out from under us mid-export.
+    #[tracing::instrument(skip(self, dest))]
+    pub fn backup_to(&self, dest: &Path, max_frame_no: u64) -> Result<(), Error> {
+        let current = self.current.load();
+        current.read_locks.fetch_add(1, Ordering::SeqCst);
+
+        let segs = self.segments.read();
+        let pinned = segs.len();
+        for seg in segs.iter() {
+            seg.read_locks.fetch_add(1, Ordering::SeqCst);
+        }
+        drop(segs);
+
+        let result = self.backup_pinned(dest, max_frame_no, current.clone());
+
+        current.read_locks.fetch_sub(1, Ordering::SeqCst);
+        for seg in self.segments.read().iter().take(pinned) {
+            seg.read_locks.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        result
+    }
+
+    /// The actual export, assuming the caller already pinned every log/segment it might read
+    /// from against concurrent checkpointing.
+    fn backup_pinned(&self, dest: &Path, max_frame_no: u64, log: Arc<Log>) -> Result<(), Error> {
+        let tx = Transaction::Read(ReadTransaction {
+            max_frame_no,
+            // TODO: once the WAL tracks a db_size history per frame_no, look up the size as of
+            // `max_frame_no` instead of assuming it hasn't shrunk since.
+            db_size: self.db_size(),
+            log,
+            created_at: Instant::now(),
+            conn_id: 0,
+            pages_read: 0,
+            observed_hlc: self.clock.now(),
+        });
+
+        let out = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dest)?;
+
+        let mut buf = [0u8; 4096];
+        for page_no in 1..=tx.db_size {
+            self.read_frame(&tx, page_no, &mut buf)?;
+            out.write_all_at(&buf, (page_no as u64 - 1) * 4096)?;
+        }
+        out.sync_all()?;
+
+        Ok(())
     }
 
     fn read_from_segments(&self, page_no: u32, max_frame_no: u64, buf: &mut [u8]) -> bool {
@@ -166,51 +380,296 @@ impl SharedWal {
         let current = self.current.load();
         current.insert_pages(pages.iter(), (size_after != 0).then_some(size_after), tx);
 
-        // TODO: use config for max log size
-        if tx.is_commited() && current.len() > 1000 {
+        if tx.is_commited() {
+            let ts = self.clock.now();
+            tx.stamp_commit(ts);
+            self.commit_hlc_index
+                .write()
+                .insert(ts, tx.next_frame_no - 1);
+        }
+
+        if tx.is_commited() && current.len() > self.checkpoint_config.max_log_frames {
             self.registry.swap_current(self, tx);
         }
 
-        // TODO: remove, stupid strategy for tests
-        // ok, we still hold a write txn
-        if self.segments.read().len() > 10 {
-            self.checkpoint()
+        if self.segments.read().len() > self.checkpoint_config.compaction_segment_threshold {
+            self.compact_segments(self.checkpoint_config.compaction_segment_threshold);
+        }
+
+        if self.segments.read().len() > self.checkpoint_config.checkpoint_segment_threshold {
+            if let Err(e) = self.checkpoint(CheckpointMode::Passive) {
+                tracing::error!("passive checkpoint failed: {e}");
+            }
         }
     }
 
-    pub fn checkpoint(&self) {
+    /// Resolve an HLC timestamp to the most recent frame_no committed at or before it, for a
+    /// caller that wants to read "as of" a timestamp observed elsewhere (e.g. on another node)
+    /// rather than a locally meaningful frame_no. Returns `None` if no commit at or before `ts`
+    /// is still indexed: either nothing had committed yet, or `checkpoint` has since dropped the
+    /// entries of every such commit.
+    pub fn frame_no_at(&self, ts: HlcTimestamp) -> Option<u64> {
+        self.commit_hlc_index
+            .read()
+            .range(..=ts)
+            .next_back()
+            .map(|(_, &frame_no)| frame_no)
+    }
+
+    /// Merge a contiguous run of sealed segments with no active readers into one larger segment,
+    /// so `read_from_segments` has fewer fst indexes to probe per page read. Unlike `checkpoint`,
+    /// this never touches `db_file`: it only shrinks how many segments a read has to walk before
+    /// it (or `db_file`) turns up the page, and it's meant to run far more often than a full
+    /// checkpoint, independently of it. Segments still pinned by an in-flight reader or backup
+    /// are left alone; they simply age out of the next run once their last reader drops.
+    #[tracing::instrument(skip(self))]
+    pub fn compact_segments(&self, max_segments: usize) -> Option<CompactionResult> {
+        let segs = self.segments.upgradable_read();
+
+        let candidates = segs.iter().collect::<Vec<_>>();
+        let run = Compactor::mergeable_run(&candidates, max_segments);
+        if run == 0 {
+            // nothing to gain from merging 0 or 1 segment
+            return None;
+        }
+
+        let inputs = &candidates[..run];
+        let dest = inputs.last().unwrap().path().with_extension("merging");
+        let merged = match Compactor::compact(inputs, &dest) {
+            Ok(merged) => merged,
+            Err(e) => {
+                tracing::error!("failed to compact segments: {e}");
+                return None;
+            }
+        };
+        let frames_merged = merged.index().len();
+
+        let old_paths = segs.with_upgraded(|segs| {
+            let old_paths = segs.drain(..run).map(|s| s.into_path()).collect::<Vec<_>>();
+            segs.push_front(merged);
+            old_paths
+        });
+
+        for path in old_paths {
+            if let Err(e) = std::fs::remove_file(path) {
+                tracing::error!("failed to remove compacted segment: {e}");
+            }
+        }
+
+        Some(CompactionResult {
+            frames_merged,
+            segments_merged: run,
+        })
+    }
+
+    /// Merge sealed segments into `db_file` and report how much was applied, so embedders can
+    /// drive checkpointing on their own schedule instead of it being an implicit side effect of
+    /// `insert_frames`. The `CheckpointConfig` thresholds only control when this happens
+    /// automatically; they don't bound what an explicit call with `mode` can do.
+    #[tracing::instrument(skip(self))]
+    pub fn checkpoint(&self, mode: CheckpointMode) -> Result<CheckpointResult, Error> {
         let mut segs = self.segments.upgradable_read();
-        let indexes = segs.iter().take_while(|s| s.read_locks.load(Ordering::SeqCst) == 0).map(|s| s.index()).collect::<Vec<_>>();
 
-        // nothing to checkpoint rn
-        if indexes.is_empty() {
-            return
+        let eligible = match mode {
+            // Only merge segments nothing is currently reading from; leave the rest for next
+            // time, same as the unconditional behavior before this was configurable.
+            CheckpointMode::Passive => segs
+                .iter()
+                .take_while(|s| s.read_locks.load(Ordering::SeqCst) == 0)
+                .count(),
+            // Wait out any readers still pinning a sealed segment so every segment queued at
+            // call time gets merged.
+            CheckpointMode::Full | CheckpointMode::Truncate | CheckpointMode::Restart => {
+                loop {
+                    if segs.iter().all(|s| s.read_locks.load(Ordering::SeqCst) == 0) {
+                        break;
+                    }
+                    drop(segs);
+                    std::thread::yield_now();
+                    segs = self.segments.upgradable_read();
+                }
+                segs.len()
+            }
+        };
+
+        if eligible == 0 {
+            return Ok(CheckpointResult::default());
         }
 
-        dbg!(indexes.len());
+        // the newest of the segments about to be checkpointed carries the highest frame_no this
+        // call is reclaiming; every commit_hlc_index entry at or below it resolves to a frame_no
+        // that's either already in db_file or about to be, so nothing can still need to resolve a
+        // timestamp to it once this checkpoint lands.
+        let reclaimed_up_to = segs[eligible - 1].header().last_commited_frame_no.get();
+
+        let indexes = segs.iter().take(eligible).map(|s| s.index()).collect::<Vec<_>>();
 
+        let mut buf = [0u8; 4096];
+        let mut frames_checkpointed = 0usize;
         let mut union = indexes.iter().collect::<OpBuilder>().union();
         while let Some((k, v)) = union.next() {
             let page_no = u32::from_be_bytes(k.try_into().unwrap());
             let v = v.iter().max_by_key(|i| i.index).unwrap();
             let seg = &segs[v.index];
-            let (_, offset) = index_entry_split(v.value);
-            self.db_file.write_all_at(seg.read_offset(offset), (page_no as u64 - 1) * 4096).unwrap();
+            let offset = v.value as u32;
+            seg.read_offset(offset, &mut buf).unwrap();
+            if let Some(cipher) = &self.cipher {
+                let tag = cipher.seal_checkpointed(page_no, &mut buf[..4096 - 8])?;
+                self.db_page_tags.write().insert(page_no, tag);
+            }
+            self.db_file.write_all_at(&buf, (page_no as u64 - 1) * 4096).unwrap();
+            frames_checkpointed += 1;
         }
 
         self.db_file.sync_all().unwrap();
-
-        let seg_count = indexes.len();
+        // tags must be durable before we drop the segments that are the only other place the
+        // pages they authenticate still live; otherwise a crash here permanently strands the
+        // page data `checkpoint` just wrote under a tag nobody can reproduce.
+        if self.cipher.is_some() {
+            self.persist_page_tags()?;
+        }
 
         drop(union);
         drop(indexes);
 
         let paths = segs.with_upgraded(|segs| {
-            segs.drain(..seg_count).map(|s| s.into_path()).collect::<Vec<_>>()
+            segs.drain(..eligible).map(|s| s.into_path()).collect::<Vec<_>>()
         });
+        let segments_checkpointed = paths.len();
 
         for path in paths {
             std::fs::remove_file(path).unwrap();
         }
+
+        // same bound every other summary structure in this series keeps: without this, a
+        // long-lived, high-throughput node grows this map for the life of the process.
+        self.commit_hlc_index
+            .write()
+            .retain(|_, &mut frame_no| frame_no > reclaimed_up_to);
+
+        if mode == CheckpointMode::Truncate {
+            self.db_file.set_len(self.db_size() as u64 * 4096).unwrap();
+        }
+
+        // TODO: CheckpointMode::Restart should also force the current log to seal and a fresh
+        // one to start, like hitting the rotation threshold early. That requires a live
+        // WriteTransaction to hand to `registry.swap_current`, which a standalone checkpoint()
+        // call doesn't have; wire this up once checkpointing can itself acquire a write lock via
+        // `begin_write`.
+
+        Ok(CheckpointResult {
+            frames_checkpointed,
+            segments_checkpointed,
+        })
+    }
+
+    /// Where `db_page_tags` is persisted, alongside `db_file` itself.
+    fn page_tags_path(&self) -> PathBuf {
+        self.db_file_path.with_extension("page-tags")
+    }
+
+    /// Durably write `db_page_tags` to `page_tags_path` as a flat sequence of
+    /// `(page_no: u32 big-endian, tag: [u8; 16])` records, via a temp-file-then-rename, so a crash
+    /// mid-write leaves the previous, still-consistent file in place instead of a half-written
+    /// one.
+    fn persist_page_tags(&self) -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        for (page_no, tag) in self.db_page_tags.read().iter() {
+            bytes.extend_from_slice(&page_no.to_be_bytes());
+            bytes.extend_from_slice(tag);
+        }
+
+        let path = self.page_tags_path();
+        let tmp_path = path.with_extension("page-tags.tmp");
+        let tmp_file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        tmp_file.write_all_at(&bytes, 0)?;
+        tmp_file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
     }
+
+    /// Load `db_page_tags` back from `page_tags_path`, for whatever constructs a `SharedWal` to
+    /// call before serving reads against an existing `db_file`. Returns an empty map if the
+    /// sidecar doesn't exist yet (a fresh namespace, or one created before encryption support).
+    pub fn load_page_tags(db_file_path: &Path) -> Result<BTreeMap<u32, [u8; 16]>, Error> {
+        let path = db_file_path.with_extension("page-tags");
+        let mut tags = BTreeMap::new();
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(tags),
+            Err(e) => return Err(e.into()),
+        };
+
+        for record in bytes.chunks_exact(4 + 16) {
+            let page_no = u32::from_be_bytes(record[..4].try_into().unwrap());
+            let mut tag = [0u8; 16];
+            tag.copy_from_slice(&record[4..]);
+            tags.insert(page_no, tag);
+        }
+
+        Ok(tags)
+    }
+}
+
+/// Controls when `insert_frames` rotates to a new log and triggers automatic compaction and
+/// checkpointing. Mirrors the knobs a real embedder would want to tune: how big a log is allowed
+/// to grow before it's sealed, how many sealed segments are allowed to pile up before a batch of
+/// them gets merged together, and how many are allowed to pile up beyond that before they get
+/// merged all the way into `db_file`.
+#[derive(Debug, Clone, Copy)]
+pub struct CheckpointConfig {
+    /// Maximum number of frames a log may hold before it's sealed and a new one started.
+    pub max_log_frames: usize,
+    /// Number of sealed segments allowed to accumulate before they're compacted into one, in
+    /// `compact_segments`. Kept well below `checkpoint_segment_threshold` so read amplification
+    /// stays bounded long before a full checkpoint is due.
+    pub compaction_segment_threshold: usize,
+    /// Number of sealed segments allowed to accumulate before an automatic checkpoint runs.
+    pub checkpoint_segment_threshold: usize,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self {
+            max_log_frames: 1000,
+            compaction_segment_threshold: 4,
+            checkpoint_segment_threshold: 10,
+        }
+    }
+}
+
+/// Analogous to SQLite's PASSIVE/FULL/TRUNCATE/RESTART checkpoint modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Only merge segments with no active readers; leave the rest for the next checkpoint.
+    Passive,
+    /// Wait for every currently sealed segment to become mergeable, then merge all of them.
+    Full,
+    /// Like `Full`, and additionally truncate `db_file` back down to its logical size once the
+    /// merge is done.
+    Truncate,
+    /// Merges the same segments as `Full`. `checkpoint` does not seal the current log for this
+    /// mode, so its effect is the same as `Full`'s.
+    Restart,
+}
+
+/// How much work a `checkpoint` call actually did, so callers driving their own schedule can
+/// tell an effective checkpoint from a no-op.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointResult {
+    pub frames_checkpointed: usize,
+    pub segments_checkpointed: usize,
+}
+
+/// How much work a `compact_segments` call actually did, so a caller driving its own background
+/// compaction schedule can tell an effective run from a no-op, the same way `CheckpointResult`
+/// does for `checkpoint`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionResult {
+    pub frames_merged: usize,
+    pub segments_merged: usize,
 }
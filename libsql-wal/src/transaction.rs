@@ -1,13 +1,20 @@
+use std::collections::BTreeMap;
 use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, atomic::Ordering};
 use std::time::Instant;
 
-use fst::Streamer;
-use fst::map::{Map, OpBuilder};
-
-use crate::log::{Log, index_entry_split};
+use crate::clock::HlcTimestamp;
+use crate::error::{Error, Result};
+use crate::log::Log;
 use crate::shared_wal::WalLock;
 
+/// A writer that has read fewer pages than this is considered to have sunk little enough cost
+/// into its transaction that yielding the write lock to a queued waiter and retrying from
+/// scratch costs less than it saves; at or above the threshold, `enter` lets it keep running
+/// even with writers queued behind it, since aborting now would throw away more work than a
+/// waiter gains by going first.
+const WRITE_LOCK_YIELD_PAGE_THRESHOLD: usize = 64;
+
 pub enum Transaction {
     Write(WriteTransaction),
     Read(ReadTransaction),
@@ -63,12 +70,16 @@ pub struct ReadTransaction {
     /// number of pages read by this transaction. This is used to determine whether a write lock
     /// will be re-acquired.
     pub pages_read: usize,
+    /// The HLC timestamp this transaction's snapshot was taken at: either the clock's value at
+    /// `begin_read` time, or the `commit_hlc` of the write transaction it was downgraded from.
+    /// Lets a replica report back exactly how caught-up its view of the world is.
+    pub observed_hlc: HlcTimestamp,
 }
 
 impl Clone for ReadTransaction {
     fn clone(&self) -> Self {
         self.log.read_locks.fetch_add(1, Ordering::SeqCst);
-        Self { max_frame_no: self.max_frame_no, log: self.log.clone(),  db_size: self.db_size, created_at: self.created_at, conn_id: self.conn_id, pages_read: self.pages_read }
+        Self { max_frame_no: self.max_frame_no, log: self.log.clone(),  db_size: self.db_size, created_at: self.created_at, conn_id: self.conn_id, pages_read: self.pages_read, observed_hlc: self.observed_hlc }
     }
 }
 
@@ -80,25 +91,75 @@ impl Drop for ReadTransaction {
 }
 
 pub struct Savepoint {
+    /// Name given by `SAVEPOINT <name>`. The base savepoint implicitly opened when the
+    /// transaction starts has no name: it can only be rolled back to, never released by name.
+    pub name: Option<String>,
     pub next_offset: u32,
     pub next_frame_no: u64,
-    pub index: Option<Map<Vec<u8>>>,
+    /// Pages written since this savepoint was opened, mapping page_no to its offset in the
+    /// current log. This is the "builder" half of the index: it only ever holds the pages
+    /// written under this savepoint, and is discarded wholesale on rollback or merged into the
+    /// parent savepoint on release.
+    pub index: BTreeMap<u32, u32>,
+}
+
+/// Merge a run of savepoint indexes into a log's committed index.
+///
+/// `savepoints` may be given in any order: entries are grouped by page_no and sorted by offset
+/// before being appended, so the resulting per-page offset list stays in increasing offset
+/// (i.e. increasing frame_no) order regardless of how the caller walked the savepoint stack.
+pub fn merge_savepoints<'a>(
+    savepoints: impl Iterator<Item = &'a BTreeMap<u32, u32>>,
+    index: &mut BTreeMap<u32, Vec<u32>>,
+) {
+    let mut by_page: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for savepoint in savepoints {
+        for (&page_no, &offset) in savepoint {
+            by_page.entry(page_no).or_default().push(offset);
+        }
+    }
+
+    for (page_no, mut offsets) in by_page {
+        offsets.sort_unstable();
+        index.entry(page_no).or_default().extend(offsets);
+    }
 }
 
 pub struct WriteTransaction {
     pub id: u64,
     /// id of the transaction currently holding the lock
     pub wal_lock: Arc<WalLock>,
+    /// the FIFO ticket this transaction was granted the write lock under; passed back to
+    /// `WalLock::release` on `downgrade` so the next queued writer, in arrival order, gets woken
+    pub ticket: u64,
     pub savepoints: Vec<Savepoint>,
     pub next_frame_no: u64,
     pub next_offset: u32,
     pub is_commited: bool,
     pub read_tx: ReadTransaction,
+    /// The HLC timestamp this transaction committed at, set by `stamp_commit` once the frames
+    /// have actually been written. `None` until then, and always `None` for a transaction that
+    /// never commits.
+    pub commit_hlc: Option<HlcTimestamp>,
 }
 
 impl WriteTransaction {
-    /// enter the lock critical section
-    pub fn enter<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+    /// Enter the lock critical section. Returns `Error::Aborted` if nobody currently holds
+    /// `wal_lock`, rolling the transaction back to its base savepoint via `reset` first so the
+    /// caller gets a clean slate and can simply restart the whole transaction rather than crash
+    /// the connection. `pages_read` also feeds a cooperative fairness check: a transaction that
+    /// hasn't read much yet yields the lock itself (also via `reset` and `Error::TransactionStolen`,
+    /// despite nothing having actually stolen anything) the next time it notices a writer queued
+    /// behind it, instead of holding the line purely because it got there first.
+    ///
+    /// There is no path by which another connection's id can displace this one's in `tx_id` while
+    /// this one is still current: `WalLock::release` always clears `tx_id` to `None` before the
+    /// next `acquire` ever sets a different id, so `tx_id` never transitions directly from
+    /// `Some(self.id)` to `Some(other_id)`. The `Some(id) if id != self.id` arm below reflects
+    /// that: it's unreachable with `WalLock` as built today, and is kept only as a defensive
+    /// fallback in case some future change to the lock ever lets it change hands without first
+    /// clearing it.
+    pub fn enter<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
         if self.is_commited {
             tracing::error!("transaction already commited");
             todo!("txn has already been commited");
@@ -107,22 +168,94 @@ impl WriteTransaction {
         let wal_lock = self.wal_lock.clone();
         let g = wal_lock.tx_id.lock();
         match *g {
-            // we still hold the lock, we can proceed
+            // we still hold the lock, we can proceed...
             Some(id) if self.id == id => {
+                // ...unless a waiter is queued and we're still cheap to restart, in which case we
+                // make room for it ourselves rather than make it starve behind us.
+                if self.pages_read < WRITE_LOCK_YIELD_PAGE_THRESHOLD && wal_lock.has_waiters() {
+                    drop(g);
+                    self.reset(0);
+                    self.savepoints[0].index.clear();
+                    wal_lock.release(self.ticket);
+                    return Err(Error::TransactionStolen);
+                }
+                drop(g);
                 f(self)
             },
-            // Somebody took the lock from us
-            Some(_) => todo!("lock stolen"),
-            None => todo!("not a transaction"),
+            // Defensive only: unreachable under `WalLock`'s current acquire/release pairing (see
+            // above), since `tx_id` always passes through `None` before a different id is set.
+            Some(_) => {
+                drop(g);
+                self.reset(0);
+                self.savepoints[0].index.clear();
+                Err(Error::TransactionStolen)
+            }
+            // The lock isn't held by anyone: we were aborted out from under ourselves.
+            None => {
+                drop(g);
+                self.reset(0);
+                self.savepoints[0].index.clear();
+                Err(Error::Aborted)
+            }
         }
     }
 
-    pub fn savepoint(&mut self) -> usize {
+    /// Open a new savepoint, optionally named by a `SAVEPOINT <name>` statement. Returns the
+    /// savepoint's id, which can be passed to `reset` to roll back to it.
+    pub fn savepoint(&mut self, name: Option<String>) -> usize {
         let savepoint_id = self.savepoints.len();
-        self.savepoints.push(Savepoint { next_offset: self.next_offset, next_frame_no: self.next_frame_no, index: None });
+        self.savepoints.push(Savepoint {
+            name,
+            next_offset: self.next_offset,
+            next_frame_no: self.next_frame_no,
+            index: BTreeMap::new(),
+        });
         savepoint_id
     }
 
+    /// Find the id of the innermost savepoint with the given name, if any is currently open.
+    pub fn find_savepoint(&self, name: &str) -> Option<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|s| s.name.as_deref() == Some(name))
+    }
+
+    /// `ROLLBACK TO SAVEPOINT <name>`: discard every page written since the named savepoint was
+    /// opened, without closing the surrounding transaction. Returns `Error::NoSuchSavepoint` if
+    /// `name` isn't currently open, rather than panicking: a typo'd or already-released name is
+    /// ordinary, connection-triggerable SQL, not an invariant violation.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<()> {
+        let id = self
+            .find_savepoint(name)
+            .ok_or_else(|| Error::NoSuchSavepoint(name.to_string()))?;
+        self.reset(id);
+        self.savepoints[id].index.clear();
+        Ok(())
+    }
+
+    /// `RELEASE SAVEPOINT <name>`: fold the named savepoint and everything opened after it into
+    /// its parent. The pages it wrote remain visible, they simply stop being individually
+    /// revertible. Returns `Error::NoSuchSavepoint` if `name` isn't currently open, same as
+    /// `rollback_to_savepoint`.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        let id = self
+            .find_savepoint(name)
+            .ok_or_else(|| Error::NoSuchSavepoint(name.to_string()))?;
+        // releasing the base savepoint is a no-op: there is nothing above it to merge, and it
+        // can only really go away when the transaction commits.
+        if id == 0 {
+            return Ok(());
+        }
+
+        let released = self.savepoints.drain(id..).collect::<Vec<_>>();
+        let parent = self.savepoints.last_mut().expect("savepoint vanished");
+        for savepoint in released {
+            parent.index.extend(savepoint.index);
+        }
+
+        Ok(())
+    }
+
     pub fn reset(&mut self, savepoint_id: usize) {
         if savepoint_id >= self.savepoints.len() {
             panic!("savepoint doesn't exist");
@@ -133,57 +266,35 @@ impl WriteTransaction {
         self.next_offset = self.savepoints.last().unwrap().next_offset;
     }
 
-    /// Returns an iterator over the current transaction index key/values
-    pub fn index_iter(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
-        let iter = self.savepoints.iter().filter_map(|s| s.index.as_ref());
-        let mut union = iter.collect::<OpBuilder>().union();
-        std::iter::from_fn(move || {
-            match union.next() {
-                Some((key, vals)) => {
-                    let key = u32::from_be_bytes(key.try_into().unwrap());
-                    let val = vals.iter().max_by_key(|i| i.index).unwrap().value;
-                    Some((key, val))
-                },
-                None => None,
+    /// Returns an iterator over the current transaction index key/values, i.e. every page
+    /// written so far in this transaction, with later savepoints shadowing earlier ones.
+    pub fn index_iter(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let mut merged = BTreeMap::new();
+        for savepoint in self.savepoints.iter() {
+            for (&page_no, &offset) in &savepoint.index {
+                merged.insert(page_no, offset);
             }
-        })
+        }
+        merged.into_iter()
+    }
+
+    /// Record the HLC timestamp this transaction committed at. Called once the transaction's
+    /// frames have been durably inserted, so that `downgrade` can hand the timestamp on to the
+    /// read transaction it produces.
+    pub(crate) fn stamp_commit(&mut self, ts: HlcTimestamp) {
+        self.commit_hlc = Some(ts);
     }
 
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self), fields(id = self.id))]
     pub fn downgrade(self) -> ReadTransaction {
         tracing::trace!("downgrading write transaction");
-        let Self { id, wal_lock, read_tx, .. } = self;
-        let mut lock = wal_lock.tx_id.lock();
-        match *lock {
-            Some(lock_id) if lock_id == id => {
-                lock.take();
-            }
-            _ => (),
-        }
-
-        if let Some(id) = *wal_lock.reserved.lock() {
-            tracing::trace!("tx already reserved by {id}");
-            return read_tx;
-        }
-
-        loop {
-            match wal_lock.waiters.steal() {
-                crossbeam::deque::Steal::Empty => {
-                    tracing::trace!("no connection waiting");
-                    break
-                },
-                crossbeam::deque::Steal::Success((unparker, id)) => {
-                    tracing::trace!("waking up {id}");
-                    wal_lock.reserved.lock().replace(id);
-                    unparker.unpark();
-                    break
-                },
-                crossbeam::deque::Steal::Retry => (),
-            }
+        let Self { wal_lock, ticket, mut read_tx, commit_hlc, .. } = self;
+        if let Some(ts) = commit_hlc {
+            read_tx.observed_hlc = ts;
         }
-
-        tracing::debug!(id=self.id, "lock released");
-
+        // release hands the lock to whoever holds the next ticket, in strict arrival order, so
+        // no writer waiting behind us can be skipped over.
+        wal_lock.release(ticket);
         read_tx
     }
 
@@ -191,15 +302,14 @@ impl WriteTransaction {
         self.is_commited
     }
 
-    pub(crate) fn find_frame(&self, page_no: u32) -> Option<(u32, u32)> {
-        let iter = self.savepoints.iter().rev().filter_map(|s| s.index.as_ref());
-        for index in iter {
-            if let Some(val) = index.get(page_no.to_be_bytes()) {
-                return Some(index_entry_split(val))
-            }
-        }
-
-        None
+    /// Find the most recent offset at which `page_no` was written in this transaction, walking
+    /// savepoints from innermost to outermost so an uncommitted write always shadows an older
+    /// one, even across a `RELEASE`.
+    pub(crate) fn find_frame(&self, page_no: u32) -> Option<u32> {
+        self.savepoints
+            .iter()
+            .rev()
+            .find_map(|s| s.index.get(&page_no).copied())
     }
 }
 
@@ -216,3 +326,118 @@ impl DerefMut for WriteTransaction {
         &mut self.read_tx
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::CompressionMode;
+    use std::num::NonZeroU64;
+    use std::sync::atomic::AtomicU64;
+
+    static TEST_LOG_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    /// A `Log` backed by a uniquely-named file under the system temp dir, removed on drop so
+    /// running these tests doesn't leave files behind.
+    struct TestLog {
+        path: std::path::PathBuf,
+        log: Arc<Log>,
+    }
+
+    impl Drop for TestLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn test_log() -> TestLog {
+        let n = TEST_LOG_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "libsql-wal-transaction-test-{}-{n}",
+            std::process::id()
+        ));
+        let log = Log::create(&path, 0, NonZeroU64::new(1).unwrap(), 0, None, CompressionMode::None)
+            .unwrap();
+        TestLog { path, log: Arc::new(log) }
+    }
+
+    fn test_write_tx(log: Arc<Log>) -> WriteTransaction {
+        WriteTransaction {
+            id: 0,
+            wal_lock: Arc::new(WalLock::default()),
+            ticket: 0,
+            savepoints: vec![Savepoint {
+                name: None,
+                next_offset: 0,
+                next_frame_no: 1,
+                index: BTreeMap::new(),
+            }],
+            next_frame_no: 1,
+            next_offset: 0,
+            is_commited: false,
+            read_tx: ReadTransaction {
+                max_frame_no: 0,
+                db_size: 0,
+                log,
+                created_at: Instant::now(),
+                conn_id: 0,
+                pages_read: 0,
+                observed_hlc: crate::clock::HybridLogicalClock::new(0).now(),
+            },
+            commit_hlc: None,
+        }
+    }
+
+    #[test]
+    fn rollback_to_unknown_savepoint_errors_instead_of_panicking() {
+        let log = test_log();
+        let mut tx = test_write_tx(log.log.clone());
+        match tx.rollback_to_savepoint("missing") {
+            Err(Error::NoSuchSavepoint(name)) => assert_eq!(name, "missing"),
+            Ok(_) => panic!("expected NoSuchSavepoint, got Ok"),
+            Err(_) => panic!("expected NoSuchSavepoint, got a different error"),
+        }
+    }
+
+    #[test]
+    fn release_unknown_savepoint_errors_instead_of_panicking() {
+        let log = test_log();
+        let mut tx = test_write_tx(log.log.clone());
+        match tx.release_savepoint("missing") {
+            Err(Error::NoSuchSavepoint(name)) => assert_eq!(name, "missing"),
+            Ok(_) => panic!("expected NoSuchSavepoint, got Ok"),
+            Err(_) => panic!("expected NoSuchSavepoint, got a different error"),
+        }
+    }
+
+    #[test]
+    fn rollback_to_named_savepoint_keeps_it_open_but_discards_what_came_after() {
+        let log = test_log();
+        let mut tx = test_write_tx(log.log.clone());
+        tx.savepoint(Some("s1".to_string()));
+        tx.next_offset = 100;
+        tx.next_frame_no = 5;
+        tx.savepoints.last_mut().unwrap().index.insert(1, 100);
+
+        tx.rollback_to_savepoint("s1").unwrap();
+
+        // s1 itself is still open, restored to the state it was opened in; only pages written
+        // since are gone.
+        assert_eq!(tx.next_offset, 0);
+        assert_eq!(tx.next_frame_no, 1);
+        assert_eq!(tx.savepoints.len(), 2);
+        assert_eq!(tx.find_frame(1), None);
+    }
+
+    #[test]
+    fn release_named_savepoint_merges_its_index_into_the_parent() {
+        let log = test_log();
+        let mut tx = test_write_tx(log.log.clone());
+        tx.savepoint(Some("s1".to_string()));
+        tx.savepoints.last_mut().unwrap().index.insert(1, 100);
+
+        tx.release_savepoint("s1").unwrap();
+
+        assert_eq!(tx.savepoints.len(), 1);
+        assert_eq!(tx.savepoints[0].index.get(&1), Some(&100));
+    }
+}
@@ -22,7 +22,7 @@ async fn main() {
         .replace("libsql", "https");
 
     let db = Builder::new_remote_replica(db_file, url, auth_token)
-        // .encryption_key("s3cr3t")
+        .encryption_key("s3cr3t")
         .build()
         .await
         .unwrap();